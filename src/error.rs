@@ -0,0 +1,64 @@
+// Copyright 2017 Dasein Phaos aka. Luxko
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! windows/d3d12 error handling
+
+use winapi::HRESULT;
+
+/// a windows/d3d12 error, wrapping the failing `HRESULT` and, where
+/// available, a human readable message extracted from the call site
+/// (e.g. an `ID3DBlob` of compiler error text)
+#[derive(Clone, Debug)]
+pub struct WinError {
+    hr: HRESULT,
+    message: Option<String>,
+}
+
+impl WinError {
+    /// the failing `HRESULT`
+    #[inline]
+    pub fn hresult(&self) -> HRESULT {
+        self.hr
+    }
+
+    #[inline]
+    pub fn from_hresult(hr: HRESULT) -> Self {
+        WinError{hr: hr, message: None}
+    }
+
+    #[inline]
+    pub fn with_message(hr: HRESULT, message: String) -> Self {
+        WinError{hr: hr, message: Some(message)}
+    }
+
+    /// turns a raw `HRESULT` into a `Result`, invoking `f` to build the
+    /// success value only when the call actually succeeded
+    #[inline]
+    pub fn from_hresult_or_ok<T, F: FnOnce() -> T>(hr: HRESULT, f: F) -> Result<T, WinError> {
+        if hr >= 0 {
+            Ok(f())
+        } else {
+            Err(WinError::from_hresult(hr))
+        }
+    }
+}
+
+impl ::std::fmt::Display for WinError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self.message {
+            Some(ref msg) => write!(f, "HRESULT 0x{:08X}: {}", self.hr, msg),
+            None => write!(f, "HRESULT 0x{:08X}", self.hr),
+        }
+    }
+}
+
+impl ::std::error::Error for WinError {
+    fn description(&self) -> &str {
+        "a windows/d3d12 call failed"
+    }
+}
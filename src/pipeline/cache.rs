@@ -0,0 +1,305 @@
+// Copyright 2017 Dasein Phaos aka. Luxko
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! a persistent, on-disk cache of compiled pipeline state objects, so a
+//! driver doesn't have to recompile the same PSO on every run.
+//!
+//! entries are keyed by a hash of the pipeline's fixed-function state plus
+//! every attached shader's bytecode, and are validated against the
+//! adapter/driver that produced them before being trusted.
+
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+
+use device::Device;
+use error::WinError;
+use comptr::ComPtr;
+use winapi::{ID3DBlob, D3D12_ERROR_ADAPTER_NOT_FOUND, D3D12_ERROR_DRIVER_VERSION_MISMATCH};
+use d3dcompiler::D3DCreateBlob;
+
+use super::{GraphicsPipelineState, GraphicsPipelineStateBuilder, GraphicsPipelineStateCache,
+            ComputePipelineState, ComputePipelineStateBuilder, ComputePipelineStateCache};
+
+/// identifies the device that produced a cached blob, so a blob built
+/// against a different adapter or driver is rejected before it ever
+/// reaches `CreateGraphicsPipelineState`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CacheHeader {
+    adapter_luid: i64,
+    driver_version: u64,
+}
+
+impl CacheHeader {
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[0..8].copy_from_slice(&(self.adapter_luid as u64).to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.driver_version.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; 16]) -> Self {
+        let mut luid_bytes = [0u8; 8];
+        let mut driver_bytes = [0u8; 8];
+        luid_bytes.copy_from_slice(&bytes[0..8]);
+        driver_bytes.copy_from_slice(&bytes[8..16]);
+        CacheHeader{
+            adapter_luid: u64::from_le_bytes(luid_bytes) as i64,
+            driver_version: u64::from_le_bytes(driver_bytes),
+        }
+    }
+}
+
+/// a directory-backed cache of `GraphicsPipelineState`/`ComputePipelineState`
+/// blobs, keyed by a hash of the building block that produced them
+#[derive(Clone, Debug)]
+pub struct PipelineCacheManager {
+    dir: PathBuf,
+    header: CacheHeader,
+}
+
+impl PipelineCacheManager {
+    /// `adapter_luid`/`driver_version` identify the device the cache is
+    /// valid for; entries written by a different adapter or driver are
+    /// rejected on load rather than fed to the driver
+    #[inline]
+    pub fn new<P: AsRef<Path>>(dir: P, adapter_luid: i64, driver_version: u64) -> Self {
+        PipelineCacheManager{
+            dir: dir.as_ref().to_owned(),
+            header: CacheHeader{adapter_luid: adapter_luid, driver_version: driver_version},
+        }
+    }
+
+    fn entry_path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.psocache", key))
+    }
+
+    fn load(&self, key: u64) -> Option<Vec<u8>> {
+        let mut file = fs::File::open(self.entry_path(key)).ok()?;
+        let mut header_bytes = [0u8; 16];
+        file.read_exact(&mut header_bytes).ok()?;
+        let header = CacheHeader::from_bytes(&header_bytes);
+        if header != self.header {
+            return None;
+        }
+        let mut blob = Vec::new();
+        file.read_to_end(&mut blob).ok()?;
+        Some(blob)
+    }
+
+    fn store(&self, key: u64, blob: &[u8]) {
+        let _ = fs::create_dir_all(&self.dir);
+        if let Ok(mut file) = fs::File::create(self.entry_path(key)) {
+            let _ = file.write_all(&self.header.to_bytes());
+            let _ = file.write_all(blob);
+        }
+    }
+
+    fn discard(&self, key: u64) {
+        let _ = fs::remove_file(self.entry_path(key));
+    }
+
+    /// builds (or loads from cache) the graphics pipeline state described
+    /// by `builder`. a stale blob, detected via a
+    /// `D3D12_ERROR_DRIVER_VERSION_MISMATCH`/`D3D12_ERROR_ADAPTER_NOT_FOUND`
+    /// result, is discarded and the build retried without it rather than
+    /// failing startup.
+    ///
+    /// `rootsig_key` must be a stable identity for `builder.rootsig`
+    /// (e.g. the bytes it was serialized from) since a live
+    /// `ID3D12RootSignature` exposes no way to recover those bytes, and its
+    /// pointer value isn't stable across process restarts.
+    pub fn build_graphics(
+        &self, builder: &mut GraphicsPipelineStateBuilder, rootsig_key: &[u8], device: &mut Device
+    ) -> Result<GraphicsPipelineState, WinError> {
+        let key = hash_graphics_builder(builder, rootsig_key);
+        let loaded = self.load(key);
+        if let Some(bytes) = loaded {
+            builder.cache = Some(blob_cache_from_bytes(bytes)?);
+        }
+
+        let mut pso = match builder.build(device) {
+            Ok(pso) => pso,
+            Err(e) if builder.cache.is_some() && is_stale_cache_error(&e) => {
+                self.discard(key);
+                builder.cache = None;
+                builder.build(device)?
+            }
+            Err(e) => return Err(e),
+        };
+        if let Ok(mut cache) = pso.cached() {
+            self.store(key, cache.as_bytes());
+        }
+        Ok(pso)
+    }
+
+    /// builds (or loads from cache) the compute pipeline state described
+    /// by `builder`, with the same stale-cache fallback as
+    /// [`build_graphics`](#method.build_graphics). see `build_graphics` for
+    /// why `rootsig_key` is needed.
+    pub fn build_compute(
+        &self, builder: &mut ComputePipelineStateBuilder, rootsig_key: &[u8], device: &mut Device
+    ) -> Result<ComputePipelineState, WinError> {
+        let key = hash_compute_builder(builder, rootsig_key);
+        let loaded = self.load(key);
+        if let Some(bytes) = loaded {
+            builder.cache = Some(blob_cache_from_bytes(bytes)?);
+        }
+
+        let mut pso = match builder.build(device) {
+            Ok(pso) => pso,
+            Err(e) if builder.cache.is_some() && is_stale_cache_error(&e) => {
+                self.discard(key);
+                builder.cache = None;
+                builder.build(device)?
+            }
+            Err(e) => return Err(e),
+        };
+        if let Ok(mut cache) = pso.cached() {
+            self.store(key, cache.as_bytes());
+        }
+        Ok(pso)
+    }
+}
+
+#[inline]
+fn is_stale_cache_error(e: &WinError) -> bool {
+    e.hresult() == D3D12_ERROR_DRIVER_VERSION_MISMATCH || e.hresult() == D3D12_ERROR_ADAPTER_NOT_FOUND
+}
+
+fn blob_cache_from_bytes<C: FromCachedBytes>(bytes: Vec<u8>) -> Result<C, WinError> {
+    unsafe {
+        let mut blob: *mut ID3DBlob = ::std::ptr::null_mut();
+        let hr = D3DCreateBlob(bytes.len() as u64, &mut blob);
+        WinError::from_hresult_or_ok(hr, || ())?;
+        let blob = ComPtr::new(blob);
+        ::std::ptr::copy_nonoverlapping(bytes.as_ptr(), blob.GetBufferPointer() as *mut u8, bytes.len());
+        Ok(C::from_blob(blob))
+    }
+}
+
+trait FromCachedBytes {
+    fn from_blob(blob: ComPtr<ID3DBlob>) -> Self;
+}
+
+impl FromCachedBytes for GraphicsPipelineStateCache {
+    #[inline]
+    fn from_blob(blob: ComPtr<ID3DBlob>) -> Self {
+        GraphicsPipelineStateCache{ptr: blob}
+    }
+}
+
+impl FromCachedBytes for ComputePipelineStateCache {
+    #[inline]
+    fn from_blob(blob: ComPtr<ID3DBlob>) -> Self {
+        ComputePipelineStateCache{ptr: blob}
+    }
+}
+
+#[inline]
+fn hash_pod<T: Copy, H: Hasher>(hasher: &mut H, value: &T) {
+    let bytes = unsafe {
+        ::std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>())
+    };
+    bytes.hash(hasher);
+}
+
+/// hashes the coarse shape of a stream-output desc (entry/stride counts and
+/// which rasterized stream is selected) so builders that differ only in
+/// stream-output state don't collide on the same cache key. the declaration
+/// entries/buffer strides themselves aren't walked, since that would mean
+/// hashing through the same kind of raw pointers that make
+/// `ID3D12RootSignature`'s address unusable as a cache key.
+fn hash_stream_output<H: Hasher>(hasher: &mut H, so: &::winapi::D3D12_STREAM_OUTPUT_DESC) {
+    hash_pod(hasher, &so.NumEntries);
+    hash_pod(hasher, &so.NumStrides);
+    hash_pod(hasher, &so.RasterizedStream);
+}
+
+fn hash_graphics_builder(builder: &mut GraphicsPipelineStateBuilder, rootsig_key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    rootsig_key.hash(&mut hasher);
+    hash_pod(&mut hasher, &builder.node_mask);
+    hash_stream_output(&mut hasher, &builder.stream_output.build().0);
+    hash_pod(&mut hasher, &builder.blend_state);
+    hash_pod(&mut hasher, &builder.sample_mask);
+    hash_pod(&mut hasher, &builder.rasterizer_state);
+    hash_pod(&mut hasher, &builder.depth_stencil_state);
+    builder.input_layout.elements.len().hash(&mut hasher);
+    for element in &builder.input_layout.elements {
+        hash_pod(&mut hasher, element);
+    }
+    hash_pod(&mut hasher, &builder.strip_cut_value);
+    hash_pod(&mut hasher, &builder.primitive_topology_type);
+    hash_pod(&mut hasher, &builder.num_render_targets);
+    hash_pod(&mut hasher, &builder.rtv_formats);
+    hash_pod(&mut hasher, &builder.dsv_format);
+    hash_pod(&mut hasher, &builder.sample_desc);
+    hash_pod(&mut hasher, &builder.flags);
+    if let Some(ref vs) = builder.vs { vs.as_bytes().hash(&mut hasher); }
+    if let Some(ref ps) = builder.ps { ps.as_bytes().hash(&mut hasher); }
+    if let Some(ref ds) = builder.ds { ds.as_bytes().hash(&mut hasher); }
+    if let Some(ref hs) = builder.hs { hs.as_bytes().hash(&mut hasher); }
+    if let Some(ref gs) = builder.gs { gs.as_bytes().hash(&mut hasher); }
+    hasher.finish()
+}
+
+fn hash_compute_builder(builder: &ComputePipelineStateBuilder, rootsig_key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    rootsig_key.hash(&mut hasher);
+    hash_pod(&mut hasher, &builder.node_mask);
+    hash_pod(&mut hasher, &builder.flags);
+    if let Some(ref cs) = builder.cs { cs.as_bytes().hash(&mut hasher); }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_header_roundtrips_through_bytes() {
+        let header = CacheHeader{adapter_luid: -42, driver_version: 0x1122_3344_5566_7788};
+        assert_eq!(CacheHeader::from_bytes(&header.to_bytes()), header);
+    }
+
+    #[test]
+    fn stream_output_hash_differs_on_entry_count() {
+        let mut a: ::winapi::D3D12_STREAM_OUTPUT_DESC = unsafe { ::std::mem::zeroed() };
+        a.NumEntries = 1;
+        let mut b: ::winapi::D3D12_STREAM_OUTPUT_DESC = unsafe { ::std::mem::zeroed() };
+        b.NumEntries = 2;
+
+        let mut ha = DefaultHasher::new();
+        hash_stream_output(&mut ha, &a);
+        let mut hb = DefaultHasher::new();
+        hash_stream_output(&mut hb, &b);
+        assert_ne!(ha.finish(), hb.finish());
+    }
+
+    #[test]
+    fn stream_output_hash_stable_for_equal_descs() {
+        let mut a: ::winapi::D3D12_STREAM_OUTPUT_DESC = unsafe { ::std::mem::zeroed() };
+        a.NumEntries = 3;
+        a.NumStrides = 1;
+        a.RasterizedStream = 0;
+        let mut b: ::winapi::D3D12_STREAM_OUTPUT_DESC = unsafe { ::std::mem::zeroed() };
+        b.NumEntries = 3;
+        b.NumStrides = 1;
+        b.RasterizedStream = 0;
+
+        let mut ha = DefaultHasher::new();
+        hash_stream_output(&mut ha, &a);
+        let mut hb = DefaultHasher::new();
+        hash_stream_output(&mut hb, &b);
+        assert_eq!(ha.finish(), hb.finish());
+    }
+}
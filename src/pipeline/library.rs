@@ -0,0 +1,121 @@
+// Copyright 2017 Dasein Phaos aka. Luxko
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! wraps `ID3D12PipelineLibrary1` for bulk PSO serialization: a single
+//! file can store hundreds of pipeline state objects, reloaded in one
+//! shot at startup instead of paying for a cached blob per PSO.
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+
+use winapi::{ID3D12PipelineLibrary1, ID3D12PipelineState};
+use comptr::ComPtr;
+use device::Device;
+use error::WinError;
+
+use super::{GraphicsPipelineState, GraphicsPipelineStateBuilder,
+            ComputePipelineState, ComputePipelineStateBuilder};
+
+/// `LoadGraphicsPipeline`/`LoadComputePipeline`/`StorePipeline` all take a
+/// `LPCWSTR` name, not a narrow C string; encode it to a NUL-terminated
+/// UTF-16 buffer before crossing the FFI boundary.
+fn to_wide(name: &str) -> Vec<u16> {
+    OsStr::new(name).encode_wide().chain(Some(0)).collect()
+}
+
+/// a single-file store of pipeline state objects. construct it from a
+/// device and, optionally, the bytes of a previously
+/// [`serialize`](#method.serialize)d library to reload everything it
+/// contains in one shot.
+#[derive(Clone, Debug)]
+pub struct PipelineLibrary {
+    ptr: ComPtr<ID3D12PipelineLibrary1>,
+}
+
+impl PipelineLibrary {
+    pub fn new(device: &mut Device, serialized: Option<&[u8]>) -> Result<Self, WinError> {
+        unsafe {
+            let (data, len) = match serialized {
+                Some(bytes) => (bytes.as_ptr() as *mut _, bytes.len() as u64),
+                None => (::std::ptr::null_mut(), 0),
+            };
+            let mut ret = ::std::mem::uninitialized();
+            let hr = device.ptr.CreatePipelineLibrary(
+                data, len, & ::dxguid::IID_ID3D12PipelineLibrary1,
+                &mut ret as *mut *mut _ as *mut *mut _
+            );
+            WinError::from_hresult_or_ok(hr, || PipelineLibrary{ptr: ComPtr::new(ret)})
+        }
+    }
+
+    /// returns the graphics pipeline state named `name` if it's already
+    /// in the library; otherwise builds it from `builder` and registers
+    /// it under `name` for next time.
+    pub fn store_graphics(
+        &mut self, name: &str, builder: &mut GraphicsPipelineStateBuilder, device: &mut Device
+    ) -> Result<GraphicsPipelineState, WinError> {
+        let name = to_wide(name);
+        let mut desc = builder.to_ffi_desc();
+        unsafe {
+            let mut ret: *mut ID3D12PipelineState = ::std::ptr::null_mut();
+            let hr = self.ptr.LoadGraphicsPipeline(
+                name.as_ptr(), &mut desc, & ::dxguid::IID_ID3D12PipelineState,
+                &mut ret as *mut *mut _ as *mut *mut _
+            );
+            if hr >= 0 {
+                return Ok(GraphicsPipelineState{ptr: ComPtr::new(ret)});
+            }
+        }
+
+        let pso = builder.build(device)?;
+        unsafe {
+            let hr = self.ptr.StorePipeline(name.as_ptr(), pso.ptr.as_mut_ptr());
+            WinError::from_hresult_or_ok(hr, || ())?;
+        }
+        Ok(pso)
+    }
+
+    /// returns the compute pipeline state named `name` if it's already in
+    /// the library; otherwise builds it from `builder` and registers it
+    /// under `name` for next time.
+    pub fn store_compute(
+        &mut self, name: &str, builder: &mut ComputePipelineStateBuilder, device: &mut Device
+    ) -> Result<ComputePipelineState, WinError> {
+        let name = to_wide(name);
+        let mut desc = builder.to_ffi_desc();
+        unsafe {
+            let mut ret: *mut ID3D12PipelineState = ::std::ptr::null_mut();
+            let hr = self.ptr.LoadComputePipeline(
+                name.as_ptr(), &mut desc, & ::dxguid::IID_ID3D12PipelineState,
+                &mut ret as *mut *mut _ as *mut *mut _
+            );
+            if hr >= 0 {
+                return Ok(ComputePipelineState{ptr: ComPtr::new(ret)});
+            }
+        }
+
+        let pso = builder.build(device)?;
+        unsafe {
+            let hr = self.ptr.StorePipeline(name.as_ptr(), pso.ptr.as_mut_ptr());
+            WinError::from_hresult_or_ok(hr, || ())?;
+        }
+        Ok(pso)
+    }
+
+    /// serializes the whole library into a single buffer, for the
+    /// caller to persist (e.g. to disk) and hand back to
+    /// [`new`](#method.new) on the next run.
+    pub fn serialize(&mut self) -> Result<Vec<u8>, WinError> {
+        unsafe {
+            let size = self.ptr.GetSerializedSize();
+            let mut buffer = vec![0u8; size as usize];
+            let hr = self.ptr.Serialize(buffer.as_mut_ptr() as *mut _, size);
+            WinError::from_hresult_or_ok(hr, || buffer)
+        }
+    }
+}
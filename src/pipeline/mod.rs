@@ -26,6 +26,9 @@ pub mod rasterizer;
 pub mod ds;
 pub mod ia;
 pub mod sampler;
+pub mod cache;
+pub mod library;
+pub mod stream;
 
 pub type SampleDesc = ::swapchain::SampleDesc;
 
@@ -82,6 +85,17 @@ impl $PSC {
             CachedBlobSizeInBytes: self.ptr.GetBufferSize(),
         }}
     }
+
+    /// view the cached blob's bytes, e.g. for persisting to disk
+    #[inline]
+    pub fn as_bytes(&mut self) -> &[u8] {
+        unsafe {
+            ::std::slice::from_raw_parts(
+                self.ptr.GetBufferPointer() as *const u8,
+                self.ptr.GetBufferSize()
+            )
+        }
+    }
 }
 
 impl $PS {
@@ -153,7 +167,10 @@ impl<'a> GraphicsPipelineStateBuilder<'a> {
         }
     }
 
-    pub fn build(&mut self, device: &mut Device) -> Result<GraphicsPipelineState, WinError> {
+    /// fills out the raw `D3D12_GRAPHICS_PIPELINE_STATE_DESC`, for
+    /// `build()` and for anything else (e.g. `pipeline::library`) that
+    /// needs the same desc without going through `CreateGraphicsPipelineState`
+    pub(crate) fn to_ffi_desc(&mut self) -> ::winapi::D3D12_GRAPHICS_PIPELINE_STATE_DESC {
         unsafe {
             let mut desc: ::winapi::D3D12_GRAPHICS_PIPELINE_STATE_DESC = ::std::mem::zeroed();
             desc.pRootSignature = self.rootsig.ptr.as_mut_ptr();
@@ -178,7 +195,13 @@ impl<'a> GraphicsPipelineStateBuilder<'a> {
             desc.NodeMask = self.node_mask;
             if let Some(ref mut pso) = self.cache { desc.CachedPSO = pso.to_ffi_cache(); }
             desc.Flags = transmute(self.flags);
+            desc
+        }
+    }
 
+    pub fn build(&mut self, device: &mut Device) -> Result<GraphicsPipelineState, WinError> {
+        unsafe {
+            let desc = self.to_ffi_desc();
             let mut ret = ::std::mem::uninitialized();
             let hr = device.ptr.CreateGraphicsPipelineState(
                 &desc, & ::dxguid::IID_ID3D12PipelineState,
@@ -213,7 +236,10 @@ impl<'a> ComputePipelineStateBuilder<'a> {
         }
     }
 
-    pub fn build(&mut self, device: &mut Device) -> Result<ComputePipelineState, WinError> {
+    /// fills out the raw `D3D12_COMPUTE_PIPELINE_STATE_DESC`, for
+    /// `build()` and for anything else (e.g. `pipeline::library`) that
+    /// needs the same desc without going through `CreateComputePipelineState`
+    pub(crate) fn to_ffi_desc(&mut self) -> ::winapi::D3D12_COMPUTE_PIPELINE_STATE_DESC {
         unsafe {
             let mut desc: ::winapi::D3D12_COMPUTE_PIPELINE_STATE_DESC = ::std::mem::zeroed();
             desc.pRootSignature = self.rootsig.ptr.as_mut_ptr();
@@ -221,7 +247,13 @@ impl<'a> ComputePipelineStateBuilder<'a> {
             desc.NodeMask = self.node_mask;
             if let Some(ref mut pso) = self.cache { desc.CachedPSO = pso.to_ffi_cache(); }
             desc.Flags = transmute(self.flags);
+            desc
+        }
+    }
 
+    pub fn build(&mut self, device: &mut Device) -> Result<ComputePipelineState, WinError> {
+        unsafe {
+            let desc = self.to_ffi_desc();
             let mut ret = ::std::mem::uninitialized();
             let hr = device.ptr.CreateComputePipelineState(
                 &desc, & ::dxguid::IID_ID3D12PipelineState,
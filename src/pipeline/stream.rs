@@ -0,0 +1,230 @@
+// Copyright 2017 Dasein Phaos aka. Luxko
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! a pipeline-state-stream builder targeting `ID3D12Device2::CreatePipelineState`,
+//! for pipeline features the legacy `D3D12_GRAPHICS_PIPELINE_STATE_DESC` in
+//! [`super::GraphicsPipelineStateBuilder`] cannot express: amplification/mesh
+//! shaders, view instancing, and newer subobjects in general.
+//!
+//! the stream itself is just a byte buffer: each subobject is its
+//! `D3D12_PIPELINE_STATE_SUBOBJECT_TYPE` tag followed by the subobject's
+//! payload struct at that struct's natural alignment (so padding may sit
+//! between the two), with every entry pointer-aligned so the
+//! runtime can walk the buffer. only subobjects that were actually set are
+//! appended, so e.g. a mesh-shader pipeline can omit `VS`/input layout
+//! entirely.
+
+use std::mem::{align_of, size_of, transmute};
+
+use winapi::{
+    D3D12_PIPELINE_STATE_STREAM_DESC,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_ROOT_SIGNATURE,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_VS,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_PS,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_AS,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_MS,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_BLEND,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_RASTERIZER,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_DEPTH_STENCIL,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_RENDER_TARGET_FORMATS,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_DEPTH_STENCIL_FORMAT,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_SAMPLE_DESC,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_FLAGS,
+    D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_VIEW_INSTANCING,
+    D3D12_RT_FORMAT_ARRAY, D3D12_VIEW_INSTANCING_DESC,
+};
+use device::Device;
+use format::DxgiFormat;
+use comptr::ComPtr;
+use error::WinError;
+use shader::{VsShaderBytecode, PsShaderBytecode, AsShaderBytecode, MsShaderBytecode};
+
+use super::{GraphicsPipelineState, PipelineStateFlags, SampleDesc};
+use super::{blend, rasterizer, ds, rootsig};
+
+#[inline]
+fn push_pod<T: Copy>(buf: &mut Vec<u8>, value: &T) {
+    let bytes = unsafe {
+        ::std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>())
+    };
+    buf.extend_from_slice(bytes);
+}
+
+#[inline]
+fn pad_to(buf: &mut Vec<u8>, align: usize) {
+    let rem = buf.len() % align;
+    if rem != 0 {
+        buf.resize(buf.len() + (align - rem), 0);
+    }
+}
+
+/// appends one subobject (its type tag, then its payload) to the stream,
+/// pointer-aligning the tag and aligning the payload to its own type's
+/// alignment, as the runtime expects
+fn push_subobject<T: Copy>(
+    buf: &mut Vec<u8>, ty: D3D12_PIPELINE_STATE_SUBOBJECT_TYPE, payload: &T
+) {
+    pad_to(buf, align_of::<usize>());
+    push_pod(buf, &ty);
+    pad_to(buf, align_of::<T>());
+    push_pod(buf, payload);
+}
+
+/// a graphics pso builder backed by `D3D12_PIPELINE_STATE_STREAM_DESC`,
+/// for subobjects the legacy [`super::GraphicsPipelineStateBuilder`]
+/// can't express
+#[derive(Clone, Debug)]
+pub struct GraphicsPipelineStreamBuilder<'a> {
+    pub rootsig: &'a rootsig::RootSig,
+    pub vs: Option<VsShaderBytecode>,
+    pub ps: Option<PsShaderBytecode>,
+    /// amplification shader; a pure mesh-shader pipeline sets this and
+    /// `ms` directly and leaves `vs` (and any input layout) unset
+    pub amp: Option<AsShaderBytecode>,
+    pub ms: Option<MsShaderBytecode>,
+    pub blend_state: Option<blend::BlendDesc>,
+    pub rasterizer_state: Option<rasterizer::RasterizerDesc>,
+    pub depth_stencil_state: Option<ds::DepthStencilDesc>,
+    pub rtv_formats: Option<([DxgiFormat; 8], u32)>,
+    pub dsv_format: Option<DxgiFormat>,
+    pub sample_desc: Option<SampleDesc>,
+    pub flags: Option<PipelineStateFlags>,
+    pub view_instancing: Option<D3D12_VIEW_INSTANCING_DESC>,
+}
+
+impl<'a> GraphicsPipelineStreamBuilder<'a> {
+    #[inline]
+    pub fn new(root_signature: &'a rootsig::RootSig) -> Self {
+        GraphicsPipelineStreamBuilder{
+            rootsig: root_signature,
+            vs: None, ps: None, amp: None, ms: None,
+            blend_state: None,
+            rasterizer_state: None,
+            depth_stencil_state: None,
+            rtv_formats: None,
+            dsv_format: None,
+            sample_desc: None,
+            flags: None,
+            view_instancing: None,
+        }
+    }
+
+    fn to_stream(&mut self) -> Vec<u8> {
+        let mut stream = Vec::new();
+        push_subobject(
+            &mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_ROOT_SIGNATURE,
+            &self.rootsig.ptr.as_mut_ptr()
+        );
+        if let Some(ref mut vs) = self.vs {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_VS, &vs.to_shader_bytecode());
+        }
+        if let Some(ref mut ps) = self.ps {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_PS, &ps.to_shader_bytecode());
+        }
+        if let Some(ref mut amp) = self.amp {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_AS, &amp.to_shader_bytecode());
+        }
+        if let Some(ref mut ms) = self.ms {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_MS, &ms.to_shader_bytecode());
+        }
+        if let Some(blend_state) = self.blend_state {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_BLEND, unsafe {
+                &transmute::<_, ::winapi::D3D12_BLEND_DESC>(blend_state)
+            });
+        }
+        if let Some(rasterizer_state) = self.rasterizer_state {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_RASTERIZER, unsafe {
+                &transmute::<_, ::winapi::D3D12_RASTERIZER_DESC>(rasterizer_state)
+            });
+        }
+        if let Some(depth_stencil_state) = self.depth_stencil_state {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_DEPTH_STENCIL, unsafe {
+                &transmute::<_, ::winapi::D3D12_DEPTH_STENCIL_DESC>(depth_stencil_state)
+            });
+        }
+        if let Some((rtv_formats, num_render_targets)) = self.rtv_formats {
+            let array = D3D12_RT_FORMAT_ARRAY{
+                RTFormats: unsafe { transmute(rtv_formats) },
+                NumRenderTargets: num_render_targets,
+            };
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_RENDER_TARGET_FORMATS, &array);
+        }
+        if let Some(dsv_format) = self.dsv_format {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_DEPTH_STENCIL_FORMAT, &dsv_format);
+        }
+        if let Some(sample_desc) = self.sample_desc {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_SAMPLE_DESC, unsafe {
+                &transmute::<_, ::winapi::DXGI_SAMPLE_DESC>(sample_desc)
+            });
+        }
+        if let Some(flags) = self.flags {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_FLAGS, unsafe {
+                &transmute::<_, ::winapi::D3D12_PIPELINE_STATE_FLAGS>(flags)
+            });
+        }
+        if let Some(ref view_instancing) = self.view_instancing {
+            push_subobject(&mut stream, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_VIEW_INSTANCING, view_instancing);
+        }
+        stream
+    }
+
+    pub fn build(&mut self, device: &mut Device) -> Result<GraphicsPipelineState, WinError> {
+        let mut stream = self.to_stream();
+        unsafe {
+            let desc = D3D12_PIPELINE_STATE_STREAM_DESC{
+                SizeInBytes: stream.len() as u64,
+                pPipelineStateSubobjectStream: stream.as_mut_ptr() as *mut _,
+            };
+            let mut ret = ::std::mem::uninitialized();
+            let hr = device.ptr.CreatePipelineState(
+                &desc, & ::dxguid::IID_ID3D12PipelineState,
+                &mut ret as *mut *mut _ as *mut *mut _
+            );
+            WinError::from_hresult_or_ok(hr, || GraphicsPipelineState{
+                ptr: ComPtr::new(ret)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pad_to_leaves_aligned_buffers_untouched() {
+        let mut buf = vec![0u8; 8];
+        pad_to(&mut buf, 8);
+        assert_eq!(buf.len(), 8);
+    }
+
+    #[test]
+    fn pad_to_rounds_up_to_the_next_alignment() {
+        let mut buf = vec![0u8; 3];
+        pad_to(&mut buf, 8);
+        assert_eq!(buf.len(), 8);
+    }
+
+    #[test]
+    fn push_subobject_aligns_tag_and_payload() {
+        let mut buf = vec![0u8; 1];
+        push_subobject(&mut buf, D3D12_PIPELINE_STATE_SUBOBJECT_TYPE_FLAGS, &0u64);
+        // the 1 leftover byte is padded out to a pointer-aligned tag, the
+        // tag itself is padded out to the payload's 8-byte alignment, then
+        // the 8-byte payload follows
+        let tag_offset = align_of::<usize>();
+        let payload_offset = {
+            let mut off = tag_offset + size_of::<D3D12_PIPELINE_STATE_SUBOBJECT_TYPE>();
+            let rem = off % align_of::<u64>();
+            if rem != 0 { off += align_of::<u64>() - rem; }
+            off
+        };
+        assert_eq!(buf.len(), payload_offset + size_of::<u64>());
+    }
+}
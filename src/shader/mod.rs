@@ -0,0 +1,159 @@
+// Copyright 2017 Dasein Phaos aka. Luxko
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! shader bytecode and runtime HLSL compilation
+
+use winapi::{D3D12_SHADER_BYTECODE, ID3DBlob};
+use comptr::ComPtr;
+use error::WinError;
+use d3dcompiler::D3DCompile;
+use std::ffi::CStr;
+
+pub mod reflect;
+
+macro_rules! define_shader_bytecode {
+    ($Name: ident) => {
+        /// precompiled shader bytecode, ready to be fed into a pipeline
+        /// state builder
+        #[derive(Clone, Debug)]
+        pub struct $Name {
+            bytes: Vec<u8>,
+        }
+
+        impl $Name {
+            /// wrap raw bytecode, e.g. as produced offline by `fxc`
+            #[inline]
+            pub fn new(bytes: Vec<u8>) -> Self {
+                $Name{bytes: bytes}
+            }
+
+            /// wrap a blob compiled at runtime via [`compile`](fn.compile.html)
+            #[inline]
+            pub fn from_blob(blob: &ShaderBlob) -> Self {
+                $Name{bytes: blob.as_bytes().to_vec()}
+            }
+
+            /// the raw bytecode bytes, e.g. for hashing into a pipeline
+            /// state cache key
+            #[inline]
+            pub(crate) fn as_bytes(&self) -> &[u8] {
+                &self.bytes
+            }
+
+            #[inline]
+            pub(crate) fn to_shader_bytecode(&mut self) -> D3D12_SHADER_BYTECODE {
+                D3D12_SHADER_BYTECODE{
+                    pShaderBytecode: self.bytes.as_ptr() as *const _,
+                    BytecodeLength: self.bytes.len() as u64,
+                }
+            }
+        }
+    }
+}
+
+define_shader_bytecode!(VsShaderBytecode);
+define_shader_bytecode!(PsShaderBytecode);
+define_shader_bytecode!(DsShaderBytecode);
+define_shader_bytecode!(HsShaderBytecode);
+define_shader_bytecode!(GsShaderBytecode);
+define_shader_bytecode!(CsShaderBytecode);
+define_shader_bytecode!(AsShaderBytecode);
+define_shader_bytecode!(MsShaderBytecode);
+
+bitflags!{
+    /// flags controlling runtime HLSL compilation, mirroring the
+    /// `D3DCOMPILE_*` constants
+    #[repr(C)]
+    pub struct ShaderCompileFlags: u32 {
+        const NONE                       = 0;
+        const DEBUG                      = 0x1;
+        const SKIP_VALIDATION            = 0x2;
+        const SKIP_OPTIMIZATION          = 0x4;
+        const PACK_MATRIX_ROW_MAJOR      = 0x8;
+        const PACK_MATRIX_COLUMN_MAJOR   = 0x10;
+        const PARTIAL_PRECISION          = 0x20;
+    }
+}
+
+impl Default for ShaderCompileFlags {
+    #[inline]
+    fn default() -> Self {
+        ShaderCompileFlags::NONE
+    }
+}
+
+/// a blob of compiled shader bytecode returned by the HLSL compiler; on
+/// failure [`compile`](fn.compile.html) returns the compiler's error text
+/// in its `WinError` instead
+#[derive(Clone, Debug)]
+pub struct ShaderBlob {
+    pub(crate) ptr: ComPtr<ID3DBlob>,
+}
+
+impl ShaderBlob {
+    #[inline]
+    pub fn get_buffer_pointer(&mut self) -> *mut u8 {
+        unsafe { self.ptr.GetBufferPointer() as *mut u8 }
+    }
+
+    #[inline]
+    pub fn get_buffer_size(&mut self) -> usize {
+        unsafe { self.ptr.GetBufferSize() }
+    }
+
+    /// view the blob's contents as a byte slice
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            ::std::slice::from_raw_parts(
+                self.ptr.GetBufferPointer() as *const u8,
+                self.ptr.GetBufferSize()
+            )
+        }
+    }
+}
+
+/// compiles HLSL source into a [`ShaderBlob`](struct.ShaderBlob.html) via
+/// `D3DCompile`. `entry` names the shader's entry point, `target` is the
+/// usual `vs_5_1`/`ps_5_1`/... profile string. On a failing `HRESULT`, the
+/// compiler's error blob (if any) is captured and surfaced in the returned
+/// `WinError`.
+pub fn compile(
+    code: &[u8], entry: &CStr, target: &CStr, flags: ShaderCompileFlags
+) -> Result<ShaderBlob, WinError> {
+    unsafe {
+        let mut blob: *mut ID3DBlob = ::std::ptr::null_mut();
+        let mut error_blob: *mut ID3DBlob = ::std::ptr::null_mut();
+        let hr = D3DCompile(
+            code.as_ptr() as *const _, code.len() as u64,
+            ::std::ptr::null(), ::std::ptr::null(), ::std::ptr::null_mut(),
+            entry.as_ptr(), target.as_ptr(),
+            flags.bits(), 0,
+            &mut blob, &mut error_blob
+        );
+        if hr < 0 {
+            // a failing compile may still leave a `blob`-shaped partial
+            // result; only the error text (if any) is worth surfacing
+            return Err(if !error_blob.is_null() {
+                let error_blob = ComPtr::new(error_blob);
+                let ptr = error_blob.GetBufferPointer() as *const u8;
+                let len = error_blob.GetBufferSize();
+                let bytes = ::std::slice::from_raw_parts(ptr, len);
+                WinError::with_message(hr, String::from_utf8_lossy(bytes).into_owned())
+            } else {
+                WinError::from_hresult(hr)
+            });
+        }
+        // a successful compile can still produce warnings in `error_blob`;
+        // drop them (releasing the COM ref) rather than failing the compile
+        if !error_blob.is_null() {
+            ComPtr::new(error_blob);
+        }
+        WinError::from_hresult_or_ok(hr, || ShaderBlob{ptr: ComPtr::new(blob)})
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright 2017 Dasein Phaos aka. Luxko
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! shader reflection via `D3DReflect`, so input layouts can be derived
+//! straight from a compiled shader instead of hand-written and silently
+//! drifting out of sync with it.
+
+use std::ffi::{CStr, CString};
+
+use winapi::{
+    ID3D12ShaderReflection, D3D12_SHADER_DESC, D3D12_SIGNATURE_PARAMETER_DESC,
+    D3D12_SHADER_INPUT_BIND_DESC, D3D_REGISTER_COMPONENT_TYPE, D3D_REGISTER_COMPONENT_FLOAT32,
+    D3D_REGISTER_COMPONENT_UINT32, D3D_REGISTER_COMPONENT_SINT32, D3D_SHADER_INPUT_TYPE,
+    D3D_SIT_CBUFFER, D3D_SIT_TBUFFER, D3D_SIT_TEXTURE, D3D_SIT_STRUCTURED,
+    D3D_SIT_BYTEADDRESS, D3D_SIT_SAMPLER, D3D_SIT_UAV_RWTYPED, D3D_SIT_UAV_RWSTRUCTURED,
+    D3D_SIT_UAV_RWBYTEADDRESS, D3D_SIT_UAV_APPEND_STRUCTURED, D3D_SIT_UAV_CONSUME_STRUCTURED,
+    D3D_SIT_UAV_RWSTRUCTURED_WITH_COUNTER, D3D12_APPEND_ALIGNED_ELEMENT,
+    D3D12_INPUT_ELEMENT_DESC, D3D12_INPUT_PER_VERTEX_DATA,
+};
+use dxguid::IID_ID3D12ShaderReflection;
+use comptr::ComPtr;
+use error::WinError;
+use format::DxgiFormat;
+use pipeline::ia;
+
+use super::ShaderBlob;
+
+/// one entry of a shader's input signature, as reported by
+/// `GetInputParameterDesc`
+#[derive(Clone, Debug)]
+pub struct InputParameter {
+    pub semantic_name: String,
+    pub semantic_index: u32,
+    pub register: u32,
+    pub component_mask: u8,
+    pub component_type: D3D_REGISTER_COMPONENT_TYPE,
+}
+
+/// how a bound resource is consumed by the shader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Cbv,
+    Srv,
+    Uav,
+    Sampler,
+    Other,
+}
+
+fn resource_kind(ty: D3D_SHADER_INPUT_TYPE) -> ResourceKind {
+    match ty {
+        D3D_SIT_CBUFFER => ResourceKind::Cbv,
+        D3D_SIT_TEXTURE | D3D_SIT_TBUFFER | D3D_SIT_STRUCTURED | D3D_SIT_BYTEADDRESS => ResourceKind::Srv,
+        D3D_SIT_UAV_RWTYPED | D3D_SIT_UAV_RWSTRUCTURED | D3D_SIT_UAV_RWBYTEADDRESS
+            | D3D_SIT_UAV_APPEND_STRUCTURED | D3D_SIT_UAV_CONSUME_STRUCTURED
+            | D3D_SIT_UAV_RWSTRUCTURED_WITH_COUNTER => ResourceKind::Uav,
+        D3D_SIT_SAMPLER => ResourceKind::Sampler,
+        _ => ResourceKind::Other,
+    }
+}
+
+/// a resource bound by the shader (CBV/SRV/UAV/sampler), for cross
+/// checking against the `rootsig::RootSig` a pipeline attaches it to
+#[derive(Clone, Debug)]
+pub struct BoundResource {
+    pub name: String,
+    pub kind: ResourceKind,
+    pub register: u32,
+    pub space: u32,
+}
+
+fn component_format(mask: u8, ty: D3D_REGISTER_COMPONENT_TYPE) -> Option<DxgiFormat> {
+    let num_components = (mask & 0xf).count_ones();
+    match (num_components, ty) {
+        (1, D3D_REGISTER_COMPONENT_FLOAT32) => Some(::format::DXGI_FORMAT_R32_FLOAT),
+        (2, D3D_REGISTER_COMPONENT_FLOAT32) => Some(::format::DXGI_FORMAT_R32G32_FLOAT),
+        (3, D3D_REGISTER_COMPONENT_FLOAT32) => Some(::format::DXGI_FORMAT_R32G32B32_FLOAT),
+        (4, D3D_REGISTER_COMPONENT_FLOAT32) => Some(::format::DXGI_FORMAT_R32G32B32A32_FLOAT),
+        (1, D3D_REGISTER_COMPONENT_UINT32) => Some(::format::DXGI_FORMAT_R32_UINT),
+        (2, D3D_REGISTER_COMPONENT_UINT32) => Some(::format::DXGI_FORMAT_R32G32_UINT),
+        (3, D3D_REGISTER_COMPONENT_UINT32) => Some(::format::DXGI_FORMAT_R32G32B32_UINT),
+        (4, D3D_REGISTER_COMPONENT_UINT32) => Some(::format::DXGI_FORMAT_R32G32B32A32_UINT),
+        (1, D3D_REGISTER_COMPONENT_SINT32) => Some(::format::DXGI_FORMAT_R32_SINT),
+        (2, D3D_REGISTER_COMPONENT_SINT32) => Some(::format::DXGI_FORMAT_R32G32_SINT),
+        (3, D3D_REGISTER_COMPONENT_SINT32) => Some(::format::DXGI_FORMAT_R32G32B32_SINT),
+        (4, D3D_REGISTER_COMPONENT_SINT32) => Some(::format::DXGI_FORMAT_R32G32B32A32_SINT),
+        _ => None,
+    }
+}
+
+/// reflects a compiled shader via `D3DReflect`, exposing its input
+/// signature and bound resources
+#[derive(Debug)]
+pub struct ShaderReflection {
+    #[allow(dead_code)]
+    ptr: ComPtr<ID3D12ShaderReflection>,
+    input_parameters: Vec<InputParameter>,
+    bound_resources: Vec<BoundResource>,
+    semantic_names: Vec<CString>,
+}
+
+impl ShaderReflection {
+    /// reflects `blob`, eagerly reading out its input signature and
+    /// bound resources
+    pub fn reflect(blob: &ShaderBlob) -> Result<Self, WinError> {
+        unsafe {
+            let bytes = blob.as_bytes();
+            let mut raw = ::std::ptr::null_mut();
+            let hr = ::d3dcompiler::D3DReflect(
+                bytes.as_ptr() as *const _, bytes.len() as u64,
+                &IID_ID3D12ShaderReflection, &mut raw as *mut *mut _ as *mut *mut _
+            );
+            WinError::from_hresult_or_ok(hr, || ())?;
+            let mut ptr = ComPtr::new(raw as *mut ID3D12ShaderReflection);
+
+            let mut desc: D3D12_SHADER_DESC = ::std::mem::zeroed();
+            let hr = ptr.GetDesc(&mut desc);
+            WinError::from_hresult_or_ok(hr, || ())?;
+
+            let mut input_parameters = Vec::with_capacity(desc.InputParameters as usize);
+            let mut semantic_names = Vec::with_capacity(desc.InputParameters as usize);
+            for i in 0..desc.InputParameters {
+                let mut p: D3D12_SIGNATURE_PARAMETER_DESC = ::std::mem::zeroed();
+                let hr = ptr.GetInputParameterDesc(i, &mut p);
+                WinError::from_hresult_or_ok(hr, || ())?;
+                let name = CStr::from_ptr(p.SemanticName).to_string_lossy().into_owned();
+                semantic_names.push(CString::new(name.clone()).unwrap_or_default());
+                input_parameters.push(InputParameter{
+                    semantic_name: name,
+                    semantic_index: p.SemanticIndex,
+                    register: p.Register,
+                    component_mask: p.Mask,
+                    component_type: p.ComponentType,
+                });
+            }
+
+            let mut bound_resources = Vec::with_capacity(desc.BoundResources as usize);
+            for i in 0..desc.BoundResources {
+                let mut b: D3D12_SHADER_INPUT_BIND_DESC = ::std::mem::zeroed();
+                let hr = ptr.GetResourceBindingDesc(i, &mut b);
+                WinError::from_hresult_or_ok(hr, || ())?;
+                bound_resources.push(BoundResource{
+                    name: CStr::from_ptr(b.Name).to_string_lossy().into_owned(),
+                    kind: resource_kind(b.Type),
+                    register: b.BindPoint,
+                    space: b.Space,
+                });
+            }
+
+            Ok(ShaderReflection{ptr: ptr, input_parameters: input_parameters, bound_resources: bound_resources, semantic_names: semantic_names})
+        }
+    }
+
+    /// the shader's input signature, one entry per semantic
+    #[inline]
+    pub fn input_parameters(&self) -> &[InputParameter] {
+        &self.input_parameters
+    }
+
+    /// CBV/SRV/UAV/sampler registers the shader binds, for cross
+    /// checking against the `rootsig::RootSig` a pipeline attaches it to
+    #[inline]
+    pub fn bound_resources(&self) -> &[BoundResource] {
+        &self.bound_resources
+    }
+
+    /// builds an `ia::InputLayoutBuilder` matching this shader's input
+    /// signature, so callers don't have to hand-write semantic/format
+    /// tuples that can silently mismatch the shader. parameters with an
+    /// `SV_*` system-value semantic, and parameters whose component layout
+    /// doesn't map to a known `DxgiFormat`, are skipped.
+    pub fn build_input_layout<'a>(&'a self) -> ia::InputLayoutBuilder<'a> {
+        let mut layout = ia::InputLayoutBuilder::default();
+        for (name, p) in self.semantic_names.iter().zip(self.input_parameters.iter()) {
+            if p.semantic_name.to_ascii_uppercase().starts_with("SV_") {
+                continue;
+            }
+            if let Some(format) = component_format(p.component_mask, p.component_type) {
+                layout.elements.push(D3D12_INPUT_ELEMENT_DESC{
+                    SemanticName: name.as_ptr(),
+                    SemanticIndex: p.semantic_index,
+                    Format: format,
+                    InputSlot: 0,
+                    AlignedByteOffset: D3D12_APPEND_ALIGNED_ELEMENT,
+                    InputSlotClass: D3D12_INPUT_PER_VERTEX_DATA,
+                    InstanceDataStepRate: 0,
+                });
+            }
+        }
+        layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tbuffer_is_classified_as_srv() {
+        assert_eq!(resource_kind(D3D_SIT_TBUFFER), ResourceKind::Srv);
+    }
+
+    #[test]
+    fn cbuffer_is_classified_as_cbv() {
+        assert_eq!(resource_kind(D3D_SIT_CBUFFER), ResourceKind::Cbv);
+    }
+
+    #[test]
+    fn sampler_is_classified_as_sampler() {
+        assert_eq!(resource_kind(D3D_SIT_SAMPLER), ResourceKind::Sampler);
+    }
+
+    #[test]
+    fn component_format_maps_float3_to_rgb32_float() {
+        assert_eq!(
+            component_format(0b0111, D3D_REGISTER_COMPONENT_FLOAT32),
+            Some(::format::DXGI_FORMAT_R32G32B32_FLOAT)
+        );
+    }
+
+    #[test]
+    fn component_format_maps_uint1_to_r32_uint() {
+        assert_eq!(
+            component_format(0b0001, D3D_REGISTER_COMPONENT_UINT32),
+            Some(::format::DXGI_FORMAT_R32_UINT)
+        );
+    }
+
+    #[test]
+    fn component_format_is_none_for_unmapped_component_count() {
+        assert_eq!(component_format(0, D3D_REGISTER_COMPONENT_FLOAT32), None);
+    }
+}